@@ -0,0 +1,261 @@
+//! Decoding of animated GIF and APNG source images into the per-frame RGBA buffers and delays
+//! needed to synthesize an ANI cursor, so a user can point `wcursorgen` at one animated file
+//! instead of hand-splitting it into numbered PNGs with per-line `<ms-delay>` values.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use gif::{ColorOutput, DecodeOptions, DisposalMethod};
+use png::{DisposeOp, Decoder as PngDecoder};
+use riff_ani::ico::IconImage;
+
+/// If `path` is a GIF (animated or still) or an APNG, fully composites every frame into a
+/// standalone RGBA `IconImage` (cursor frames cannot reference previous frames, unlike the source
+/// formats) and returns them paired with their delay in milliseconds. Returns `Ok(None)` for any
+/// other file, including a still PNG, so the caller can fall back to reading it as a single
+/// static PNG.
+pub fn decode_animated(
+    path: &Path,
+    x_hot: u16,
+    y_hot: u16,
+) -> Result<Option<Vec<(IconImage, u32)>>> {
+    match sniff_format(path)? {
+        Some(Format::Gif) => Ok(Some(decode_gif(path, x_hot, y_hot)?)),
+        Some(Format::Apng) => Ok(Some(decode_apng(path, x_hot, y_hot)?)),
+        None => Ok(None),
+    }
+}
+
+enum Format {
+    Gif,
+    Apng,
+}
+
+fn sniff_format(path: &Path) -> Result<Option<Format>> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)
+        .with_context(|| format!("cannot open image file {}", path.to_string_lossy()))?;
+    let n = file.read(&mut magic).unwrap_or(0);
+
+    if n >= 6 && &magic[..3] == b"GIF" {
+        return Ok(Some(Format::Gif));
+    }
+    if n == 8 && magic == [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a] && has_actl(path)? {
+        return Ok(Some(Format::Apng));
+    }
+    Ok(None)
+}
+
+/// `acTL` must appear before the first `IDAT` in a valid APNG, so a plain chunk walk is enough to
+/// tell an animated PNG apart from a regular still one without pulling in a full APNG decoder.
+fn has_actl(path: &Path) -> Result<bool> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("cannot read PNG file {}", path.to_string_lossy()))?;
+
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        if kind == b"acTL" {
+            return Ok(true);
+        }
+        if kind == b"IDAT" {
+            return Ok(false);
+        }
+        pos += 8 + len + 4;
+    }
+    Ok(false)
+}
+
+fn decode_gif(path: &Path, x_hot: u16, y_hot: u16) -> Result<Vec<(IconImage, u32)>> {
+    let file =
+        File::open(path).with_context(|| format!("cannot open GIF file {}", path.to_string_lossy()))?;
+
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(file)
+        .with_context(|| format!("cannot read GIF file {}", path.to_string_lossy()))?;
+
+    let width = decoder.width() as usize;
+    let height = decoder.height() as usize;
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut previous = canvas.clone();
+    let global_palette = decoder.global_palette().map(<[u8]>::to_vec);
+
+    let mut result = Vec::new();
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .with_context(|| format!("cannot decode GIF frame in {}", path.to_string_lossy()))?
+    {
+        if frame.dispose == DisposalMethod::Previous {
+            previous.copy_from_slice(&canvas);
+        }
+
+        let palette = frame
+            .palette
+            .as_deref()
+            .or(global_palette.as_deref())
+            .ok_or_else(|| anyhow!("GIF frame in {} has no color palette", path.to_string_lossy()))?;
+
+        let left = frame.left as usize;
+        let top = frame.top as usize;
+        for y in 0..frame.height as usize {
+            for x in 0..frame.width as usize {
+                let index = frame.buffer[y * frame.width as usize + x];
+                if Some(index) == frame.transparent {
+                    continue;
+                }
+
+                let (cx, cy) = (left + x, top + y);
+                if cx >= width || cy >= height {
+                    continue;
+                }
+
+                let rgb = &palette[index as usize * 3..index as usize * 3 + 3];
+                let offset = (cy * width + cx) * 4;
+                canvas[offset..offset + 3].copy_from_slice(rgb);
+                canvas[offset + 3] = 255;
+            }
+        }
+
+        let mut image = IconImage::from_rgba_data(width as u32, height as u32, canvas.clone());
+        image.set_cursor_hotspot(Some((x_hot, y_hot)));
+        // GIF delays are in 1/100s; cursor ms_delay is in milliseconds.
+        result.push((image, frame.delay as u32 * 10));
+
+        match frame.dispose {
+            DisposalMethod::Background => {
+                clear_rect(&mut canvas, width, left, top, frame.width as usize, frame.height as usize)
+            }
+            DisposalMethod::Previous => canvas.copy_from_slice(&previous),
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn decode_apng(path: &Path, x_hot: u16, y_hot: u16) -> Result<Vec<(IconImage, u32)>> {
+    let file = File::open(path)
+        .with_context(|| format!("cannot open APNG file {}", path.to_string_lossy()))?;
+
+    let mut reader = PngDecoder::new(file)
+        .read_info()
+        .with_context(|| format!("cannot read APNG file {}", path.to_string_lossy()))?;
+
+    let width = reader.info().width as usize;
+    let height = reader.info().height as usize;
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut previous = canvas.clone();
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+
+    let mut result = Vec::new();
+    loop {
+        let frame_info = match reader.next_frame(&mut buf) {
+            Ok(info) => info,
+            Err(png::DecodingError::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("cannot decode APNG frame in {}", path.to_string_lossy()))
+            }
+        };
+
+        // A frame with no fcTL is the APNG default image: a non-animated "poster" IDAT shown to
+        // viewers that don't understand acTL/fcTL/fdAT. It isn't part of the animation, so skip
+        // it instead of failing the whole conversion.
+        let Some(fc) = reader.info().frame_control() else {
+            continue;
+        };
+        let delay_ms = if fc.delay_den == 0 {
+            fc.delay_num as u32 * 10
+        } else {
+            (fc.delay_num as u32 * 1000) / fc.delay_den as u32
+        };
+
+        if fc.dispose_op == DisposeOp::Previous {
+            previous.copy_from_slice(&canvas);
+        }
+
+        blend_frame(
+            &mut canvas,
+            width,
+            &buf[..frame_info.buffer_size],
+            fc.x_offset as usize,
+            fc.y_offset as usize,
+            fc.width as usize,
+            fc.height as usize,
+            fc.blend_op,
+        );
+
+        let mut image = IconImage::from_rgba_data(width as u32, height as u32, canvas.clone());
+        image.set_cursor_hotspot(Some((x_hot, y_hot)));
+        result.push((image, delay_ms));
+
+        match fc.dispose_op {
+            DisposeOp::Background => clear_rect(
+                &mut canvas,
+                width,
+                fc.x_offset as usize,
+                fc.y_offset as usize,
+                fc.width as usize,
+                fc.height as usize,
+            ),
+            DisposeOp::Previous => canvas.copy_from_slice(&previous),
+            DisposeOp::None => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn blend_frame(
+    canvas: &mut [u8],
+    canvas_width: usize,
+    rgba: &[u8],
+    left: usize,
+    top: usize,
+    w: usize,
+    h: usize,
+    blend_op: png::BlendOp,
+) {
+    for y in 0..h {
+        for x in 0..w {
+            let src = &rgba[(y * w + x) * 4..(y * w + x) * 4 + 4];
+            let offset = ((top + y) * canvas_width + (left + x)) * 4;
+            let dst = &mut canvas[offset..offset + 4];
+
+            if blend_op == png::BlendOp::Source || src[3] == 255 || dst[3] == 0 {
+                dst.copy_from_slice(src);
+                continue;
+            }
+
+            let src_a = src[3] as f32 / 255.0;
+            let dst_a = dst[3] as f32 / 255.0;
+            let out_a = src_a + dst_a * (1.0 - src_a);
+            for c in 0..3 {
+                let blended = if out_a == 0.0 {
+                    0.0
+                } else {
+                    (src[c] as f32 * src_a + dst[c] as f32 * dst_a * (1.0 - src_a)) / out_a
+                };
+                dst[c] = blended.round() as u8;
+            }
+            dst[3] = (out_a * 255.0).round() as u8;
+        }
+    }
+}
+
+fn clear_rect(canvas: &mut [u8], canvas_width: usize, left: usize, top: usize, w: usize, h: usize) {
+    for y in top..top + h {
+        let offset = (y * canvas_width + left) * 4;
+        canvas[offset..offset + w * 4].fill(0);
+    }
+}
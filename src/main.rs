@@ -7,6 +7,19 @@ use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+mod anim;
+mod quantize;
+mod resize;
+mod xcursor;
+
+use anim::decode_animated;
+use quantize::{quantize_image, QualityRange};
+
+/// Practical ceiling for a cursor image's width/height: well beyond anything Windows actually
+/// displays, but enough to catch an accidentally-unscaled source image before it produces a
+/// broken cursor file.
+const MAX_CURSOR_DIMENSION: u32 = 2048;
+
 /// This program reads the config file to find the list of cursor x2 in PNG format along with
 /// their hotspot and nominal size, then converts all of the x2 to CUR or ANI format.
 ///
@@ -18,6 +31,14 @@ use std::path::{Path, PathBuf};
 /// on each line indicates how long each image should be displayed before switching to the next.
 /// <ms-delay> can be elided for static cursors.
 ///
+/// If <filename> is an animated GIF or APNG and <ms-delay> is elided, its frames and per-frame
+/// delays are decoded straight from the file instead, so a whole animation can be given as one
+/// config line.
+///
+/// Instead of a text config, `--config` may also point directly at a compiled X11 Xcursor binary
+/// (detected by its `Xcur` magic); its images are grouped by nominal size and converted the same
+/// way as a hand-written config would be.
+///
 /// Note: on Windows, the frame rate of animated cursor is in terms of jiffies (1/60 sec), so the
 /// difference of <ms-delay> will not take effect precisely. For example, both `30 ms` and `40 ms`
 /// result in `round(30 / 16.667) = round(40 / 16.667) = 2 jiffies` in the generated cursor file.
@@ -34,10 +55,57 @@ struct Opts {
     /// automatically appended according to whether the cursor is animated)
     #[clap(short, long)]
     output: PathBuf,
-    /// Choose which size to generate. Unlike xcursor, one ANI file cannot contain multiple x2
-    /// in different sizes, so we must pick up one. The size specified must exist in the config.
+    /// Choose which size to generate. The size specified must exist in the config. Ignored
+    /// (and may be omitted) when `--all-sizes` is given.
     #[clap(short, long)]
-    size: u16,
+    size: Option<u16>,
+    /// Pack every size found in the config into a single multi-resolution .cur/.ani, instead of
+    /// picking one size with `--size`. Every size must define the same number of frames so that
+    /// they can be matched up by position; for animated cursors, the delay is taken from the
+    /// smallest size and the other sizes' delays are not checked.
+    #[clap(long)]
+    all_sizes: bool,
+    /// Run each embedded image's RGBA buffer through a median-cut color quantizer (≤256 colors,
+    /// optionally dithered) and embed it as a real 8-bit indexed PNG instead of a truecolor one.
+    /// Frames that cannot meet `--quality`'s minimum, and frames that already have 256 colors or
+    /// fewer, are left as truecolor PNGs.
+    #[clap(long)]
+    quantize: bool,
+    /// Quality range for `--quantize`, e.g. `70-95`. Quantization of a frame is skipped if it
+    /// cannot reach the minimum; dithering is skipped once the undithered result already reaches
+    /// the maximum. Defaults to `70-100`.
+    #[clap(long, default_value = "70-100")]
+    quality: QualityRange,
+    /// Choose how to encode each embedded cursor image: `png` (default) is only understood by
+    /// Windows Vista and later, `bmp` writes a classic bottom-up 32-bit DIB with an AND mask for
+    /// pre-Vista Windows, and `auto` embeds both so the file works everywhere.
+    #[clap(long, default_value = "png")]
+    encoding: Encoding,
+    /// Resample each loaded image to its config line's declared <size> (scaling the hotspot the
+    /// same way), instead of just warning when they differ. Lets one master PNG serve sizes it
+    /// wasn't rendered at.
+    #[clap(long)]
+    scale: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Png,
+    Bmp,
+    Auto,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "png" => Ok(Encoding::Png),
+            "bmp" => Ok(Encoding::Bmp),
+            "auto" => Ok(Encoding::Auto),
+            _ => Err(anyhow!("--encoding must be one of png, bmp, auto")),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -45,7 +113,32 @@ struct FrameConfig {
     size: u16,
     x_hot: u16,
     y_hot: u16,
-    path: PathBuf,
+    source: FrameSource,
+    ms_delay: u32,
+}
+
+/// Where a `FrameConfig`'s pixels come from: a PNG (or animated GIF/APNG) file on disk named in
+/// the text config, or an image already decoded in memory (currently only produced by the
+/// Xcursor parser, which has no on-disk PNG to point back to).
+enum FrameSource {
+    Png(PathBuf),
+    Inline(IconImage),
+}
+
+impl std::fmt::Debug for FrameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameSource::Png(path) => f.debug_tuple("Png").field(path).finish(),
+            FrameSource::Inline(_) => f.write_str("Inline(..)"),
+        }
+    }
+}
+
+/// A single resolved cursor frame, ready to be encoded into an `IconDirEntry`. One `FrameConfig`
+/// line expands into exactly one `Frame`, except an animated GIF/APNG source line, which expands
+/// into one `Frame` per frame of the source animation.
+struct Frame {
+    image: IconImage,
     ms_delay: u32,
 }
 
@@ -57,17 +150,36 @@ fn main() -> Result<()> {
     }
 
     let config = parse_config(&opts.config)?;
-    match config.get(&opts.size) {
-        None => Err(anyhow!("the size does not exist in the config")),
-        Some(frames) => match frames.as_slice() {
-            [] => unreachable!(),
-            [x] => generate_cur(x, &opts),
-            xs => generate_ani(xs, &opts),
-        },
+
+    if opts.all_sizes {
+        return generate_all_sizes(&config, &opts);
+    }
+
+    let size = opts
+        .size
+        .ok_or_else(|| anyhow!("--size is required unless --all-sizes is given"))?;
+    let frames = config
+        .get(&size)
+        .ok_or_else(|| anyhow!("the size does not exist in the config"))?;
+    let frames = resolve_frames(frames, &opts)?;
+
+    match frames.as_slice() {
+        [] => unreachable!(),
+        [x] => generate_cur(&x.image, &opts),
+        xs => generate_ani(xs, size, &opts),
     }
 }
 
 fn parse_config(path: &Path) -> Result<HashMap<u16, Vec<FrameConfig>>> {
+    let mut magic = [0u8; 4];
+    let read = File::open(path)
+        .context("cannot open config file")?
+        .read(&mut magic)
+        .unwrap_or(0);
+    if read == 4 && magic == *xcursor::MAGIC {
+        return xcursor::parse(path);
+    }
+
     let mut data = String::new();
     File::open(path)
         .context("cannot open config file")?
@@ -95,7 +207,7 @@ fn parse_config_line(line: &str) -> Result<FrameConfig, &'static str> {
             size: cols[0].parse().map_err(|_| "<size> must be an integer")?,
             x_hot: cols[1].parse().map_err(|_| "<x-hot> must be an integer")?,
             y_hot: cols[2].parse().map_err(|_| "<y-hot> must be an integer")?,
-            path: cols[3].into(),
+            source: FrameSource::Png(cols[3].into()),
             ms_delay: {
                 let value = cols.get(4).cloned().unwrap_or("0");
                 value.parse().map_err(|_| "<ms-delay> must be an integer")?
@@ -105,25 +217,113 @@ fn parse_config_line(line: &str) -> Result<FrameConfig, &'static str> {
     }
 }
 
-fn generate_cur(frame: &FrameConfig, opts: &Opts) -> Result<()> {
-    let mut filename = opts.output.file_name().unwrap().to_os_string();
-    filename.push(".cur");
+/// Flattens a size's `FrameConfig` lines into resolved `Frame`s, expanding any animated GIF/APNG
+/// source line into one `Frame` per decoded frame of the source animation.
+fn resolve_frames(frames: &[FrameConfig], opts: &Opts) -> Result<Vec<Frame>> {
+    frames
+        .iter()
+        .map(|frame| load_frame(frame, opts))
+        .collect::<Result<Vec<_>>>()
+        .map(|frames| frames.into_iter().flatten().collect())
+}
 
-    let cur = create_cur(frame, opts)?;
+fn load_frame(frame: &FrameConfig, opts: &Opts) -> Result<Vec<Frame>> {
+    let raw = match &frame.source {
+        FrameSource::Inline(image) => {
+            let mut image = image.clone();
+            image.set_cursor_hotspot(Some((frame.x_hot, frame.y_hot)));
+            vec![Frame {
+                image,
+                ms_delay: frame.ms_delay,
+            }]
+        }
+        FrameSource::Png(rel_path) => {
+            let path = match &opts.prefix {
+                Some(prefix) => prefix.join(rel_path),
+                None => rel_path.clone(),
+            };
 
-    let dest = opts.output.with_file_name(filename);
-    let out = File::create(&dest).with_context(|| {
-        let p = dest.to_string_lossy();
-        format!("cannot create cursor file {}", p)
+            if frame.ms_delay == 0 {
+                if let Some(decoded) = decode_animated(&path, frame.x_hot, frame.y_hot)? {
+                    decoded
+                        .into_iter()
+                        .map(|(image, ms_delay)| Frame { image, ms_delay })
+                        .collect()
+                } else {
+                    vec![read_static_frame(&path, frame)?]
+                }
+            } else {
+                vec![read_static_frame(&path, frame)?]
+            }
+        }
+    };
+
+    raw.into_iter()
+        .map(|f| finalize_frame(f, frame.size, opts))
+        .collect()
+}
+
+fn read_static_frame(path: &Path, frame: &FrameConfig) -> Result<Frame> {
+    let file = File::open(path).with_context(|| {
+        let p = path.to_string_lossy();
+        format!("cannot open PNG file {}", p)
     })?;
 
-    cur.write(&out).with_context(|| {
-        let p = dest.to_string_lossy();
-        format!("cannot write cursor file {}", p)
+    let mut image = IconImage::read_png(file).with_context(|| {
+        let p = path.to_string_lossy();
+        format!("cannot read PNG file {}", p)
+    })?;
+    image.set_cursor_hotspot(Some((frame.x_hot, frame.y_hot)));
+
+    Ok(Frame {
+        image,
+        ms_delay: frame.ms_delay,
     })
 }
 
-fn generate_ani(frames: &[FrameConfig], opts: &Opts) -> Result<()> {
+/// Either resamples a frame to its config line's declared `size` (with `--scale`) or just warns
+/// about the mismatch, then rejects whatever dimensions the frame ends up with if they still
+/// exceed `MAX_CURSOR_DIMENSION`. Resampling happens first so a single oversized master image
+/// (exactly what `--scale` exists to support) isn't rejected for a size it never ships at.
+fn finalize_frame(mut frame: Frame, size: u16, opts: &Opts) -> Result<Frame> {
+    let width = frame.image.width();
+    let height = frame.image.height();
+
+    if width != size as u32 || height != size as u32 {
+        if opts.scale {
+            frame.image = resize::resize(&frame.image, size);
+        } else {
+            eprintln!(
+                "warning: cursor image is {}x{} but the config declares size {}",
+                width, height, size
+            );
+        }
+    }
+
+    let width = frame.image.width();
+    let height = frame.image.height();
+    if width > MAX_CURSOR_DIMENSION || height > MAX_CURSOR_DIMENSION {
+        return Err(anyhow!(
+            "cursor image is {}x{}, which exceeds the {max}x{max} practical limit for cursor images",
+            width,
+            height,
+            max = MAX_CURSOR_DIMENSION
+        ));
+    }
+
+    Ok(frame)
+}
+
+fn generate_cur(image: &IconImage, opts: &Opts) -> Result<()> {
+    let mut filename = opts.output.file_name().unwrap().to_os_string();
+    filename.push(".cur");
+
+    let cur = create_cur(image, opts)?;
+
+    write_cur_file(&cur, &opts.output.with_file_name(filename))
+}
+
+fn generate_ani(frames: &[Frame], size: u16, opts: &Opts) -> Result<()> {
     if frames.iter().any(|x| x.ms_delay == 0) {
         return Err(anyhow!(
             "the <ms-delay> must be specified for animated cursor"
@@ -137,50 +337,141 @@ fn generate_ani(frames: &[FrameConfig], opts: &Opts) -> Result<()> {
         header: AniHeader {
             num_frames: frames.len() as u32,
             num_steps: frames.len() as u32,
-            width: opts.size as u32,
-            height: opts.size as u32,
+            width: size as u32,
+            height: size as u32,
             frame_rate: (frames[0].ms_delay as f32 / 16.667).round() as u32,
         },
         frames: frames
             .iter()
-            .map(|x| create_cur(x, opts))
+            .map(|x| create_cur(&x.image, opts))
             .collect::<Result<_>>()?,
     };
 
-    let dest = opts.output.with_file_name(filename);
-    let out = File::create(&dest).with_context(|| {
+    write_ani_file(&ani, &opts.output.with_file_name(filename))
+}
+
+/// Packs every size in `config` into a single multi-resolution .cur/.ani. Every size must resolve
+/// to the same number of frames so that they can be matched up by position: a static cursor gets
+/// one `IconDir` with one `IconDirEntry` per size, an animated cursor gets one such `IconDir` per
+/// ANI frame, with the delay taken from the frames of the first (numerically smallest) size.
+fn generate_all_sizes(config: &HashMap<u16, Vec<FrameConfig>>, opts: &Opts) -> Result<()> {
+    let mut sizes: Vec<_> = config.keys().copied().collect();
+    sizes.sort_unstable();
+
+    let first = *sizes.first().ok_or_else(|| anyhow!("the config file is empty"))?;
+    let resolved: HashMap<u16, Vec<Frame>> = sizes
+        .iter()
+        .map(|&size| Ok((size, resolve_frames(&config[&size], opts)?)))
+        .collect::<Result<_>>()?;
+
+    let frame_count = resolved[&first].len();
+    if sizes.iter().any(|size| resolved[size].len() != frame_count) {
+        return Err(anyhow!(
+            "--all-sizes requires every size in the config to resolve to the same number of frames"
+        ));
+    }
+
+    let icon_dirs = (0..frame_count)
+        .map(|i| {
+            let mut icon_dir = IconDir::new(ResourceType::Cursor);
+            for size in &sizes {
+                for entry in encode_entries(&resolved[size][i].image, opts)? {
+                    icon_dir.add_entry(entry);
+                }
+            }
+            Ok(icon_dir)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    match icon_dirs.as_slice() {
+        [] => unreachable!(),
+        [cur] => {
+            let mut filename = opts.output.file_name().unwrap().to_os_string();
+            filename.push(".cur");
+            write_cur_file(cur, &opts.output.with_file_name(filename))
+        }
+        _ => {
+            let frames = &resolved[&first];
+            if frames.iter().any(|x| x.ms_delay == 0) {
+                return Err(anyhow!(
+                    "the <ms-delay> must be specified for animated cursor"
+                ));
+            }
+
+            let mut filename = opts.output.file_name().unwrap().to_os_string();
+            filename.push(".ani");
+
+            let ani = Ani {
+                header: AniHeader {
+                    num_frames: icon_dirs.len() as u32,
+                    num_steps: icon_dirs.len() as u32,
+                    width: first as u32,
+                    height: first as u32,
+                    frame_rate: (frames[0].ms_delay as f32 / 16.667).round() as u32,
+                },
+                frames: icon_dirs,
+            };
+
+            write_ani_file(&ani, &opts.output.with_file_name(filename))
+        }
+    }
+}
+
+fn write_cur_file(cur: &IconDir, dest: &Path) -> Result<()> {
+    let out = File::create(dest).with_context(|| {
         let p = dest.to_string_lossy();
         format!("cannot create cursor file {}", p)
     })?;
 
-    ani.encode(&out).with_context(|| {
+    cur.write(&out).with_context(|| {
         let p = dest.to_string_lossy();
         format!("cannot write cursor file {}", p)
-    })?;
-
-    Ok(())
+    })
 }
 
-fn create_cur(frame: &FrameConfig, opts: &Opts) -> Result<IconDir> {
-    let path = match &opts.prefix {
-        Some(prefix) => prefix.join(&frame.path),
-        None => frame.path.clone(),
-    };
-
-    let file = std::fs::File::open(&path).with_context(|| {
-        let p = path.to_string_lossy();
-        format!("cannot open PNG file {}", p)
+fn write_ani_file(ani: &Ani, dest: &Path) -> Result<()> {
+    let out = File::create(dest).with_context(|| {
+        let p = dest.to_string_lossy();
+        format!("cannot create cursor file {}", p)
     })?;
 
-    let mut image = IconImage::read_png(file).with_context(|| {
-        let p = path.to_string_lossy();
-        format!("cannot read PNG file {}", p)
-    })?;
-    image.set_cursor_hotspot(Some((frame.x_hot, frame.y_hot)));
+    ani.encode(&out).with_context(|| {
+        let p = dest.to_string_lossy();
+        format!("cannot write cursor file {}", p)
+    })
+}
 
-    let entry = IconDirEntry::encode_as_png(&image).context("cannot encode PNG to CUR/ANI")?;
+fn create_cur(image: &IconImage, opts: &Opts) -> Result<IconDir> {
     let mut icon_dir = IconDir::new(ResourceType::Cursor);
-    icon_dir.add_entry(entry);
+    for entry in encode_entries(image, opts)? {
+        icon_dir.add_entry(entry);
+    }
 
     Ok(icon_dir)
 }
+
+/// Encodes `image` per `--encoding`: one PNG entry, one BMP/DIB entry, or (for `auto`) both, so
+/// the same `IconDir` can carry a Vista+ PNG entry alongside a pre-Vista DIB entry.
+fn encode_entries(image: &IconImage, opts: &Opts) -> Result<Vec<IconDirEntry>> {
+    let mut entries = Vec::new();
+
+    if matches!(opts.encoding, Encoding::Png | Encoding::Auto) {
+        let indexed = opts.quantize.then(|| quantize_image(image, opts.quality)).flatten();
+        let entry = match indexed {
+            Some(png_data) => {
+                let png_data = png_data.context("cannot quantize image to indexed PNG")?;
+                let hotspot = image.cursor_hotspot();
+                IconDirEntry::encode_as_png_data(image.width(), image.height(), hotspot, png_data)
+                    .context("cannot embed indexed PNG in CUR/ANI")?
+            }
+            None => IconDirEntry::encode_as_png(image).context("cannot encode PNG to CUR/ANI")?,
+        };
+        entries.push(entry);
+    }
+
+    if matches!(opts.encoding, Encoding::Bmp | Encoding::Auto) {
+        entries.push(IconDirEntry::encode_as_bmp(image).context("cannot encode BMP/DIB to CUR/ANI")?);
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,297 @@
+//! Median-cut color quantization for the RGBA buffers embedded in cursor frames, used by
+//! `--quantize` to shrink `.cur`/`.ani` output that would otherwise carry dozens of full 32-bit
+//! PNGs. The palette is built as RGBA (not RGB with a side alpha table) so translucent colors
+//! quantize as a single unit instead of drifting independently from their alpha.
+//!
+//! `riff_ani::ico::IconDirEntry::encode_as_png` only ever emits 8-bit-per-channel truecolor PNG
+//! (it has no indexed/palette mode), so this module encodes the quantized frame into a real 8-bit
+//! indexed PNG itself (`PLTE` palette plus a `tRNS` table for the alpha each palette entry
+//! carries) via the `png` crate, and hands the caller the raw file bytes to embed directly with
+//! `IconDirEntry::encode_as_png_data` rather than going through `encode_as_png`'s RGBA-only path.
+
+use anyhow::{anyhow, Context, Result};
+use png::{BitDepth, ColorType, Encoder};
+use riff_ani::ico::IconImage;
+
+const MAX_COLORS: usize = 256;
+
+/// A quality range expressed as a percentage: quantization is skipped for any frame whose
+/// resulting quality would fall below `min`, and dithering is skipped once quality already
+/// reaches `max` (further error diffusion would only cost size for no visible gain).
+#[derive(Debug, Clone, Copy)]
+pub struct QualityRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl std::str::FromStr for QualityRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("quality range must look like `70-95`"))?;
+        let min: u8 = min.parse().map_err(|_| anyhow!("invalid minimum quality `{}`", min))?;
+        let max: u8 = max.parse().map_err(|_| anyhow!("invalid maximum quality `{}`", max))?;
+        if min > max || max > 100 {
+            return Err(anyhow!("quality range must satisfy 0 <= min <= max <= 100"));
+        }
+        Ok(QualityRange { min, max })
+    }
+}
+
+struct ColorBox {
+    colors: Vec<([u8; 4], u32)>,
+}
+
+impl ColorBox {
+    fn channel_range(&self, channel: usize) -> (u8, u8) {
+        let mut lo = u8::MAX;
+        let mut hi = u8::MIN;
+        for (color, _) in &self.colors {
+            lo = lo.min(color[channel]);
+            hi = hi.max(color[channel]);
+        }
+        (lo, hi)
+    }
+
+    fn longest_axis(&self) -> usize {
+        (0..4)
+            .max_by_key(|&c| {
+                let (lo, hi) = self.channel_range(c);
+                hi - lo
+            })
+            .unwrap()
+    }
+
+    fn weighted_variance(&self) -> f64 {
+        let axis = self.longest_axis();
+        let total: u64 = self.colors.iter().map(|(_, n)| *n as u64).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let mean = self
+            .colors
+            .iter()
+            .map(|(color, n)| color[axis] as f64 * *n as f64)
+            .sum::<f64>()
+            / total as f64;
+        self.colors
+            .iter()
+            .map(|(color, n)| (color[axis] as f64 - mean).powi(2) * *n as f64)
+            .sum::<f64>()
+    }
+
+    fn average(&self) -> [u8; 4] {
+        let total: u64 = self.colors.iter().map(|(_, n)| *n as u64).sum();
+        let mut sum = [0u64; 4];
+        for (color, n) in &self.colors {
+            for c in 0..4 {
+                sum[c] += color[c] as u64 * *n as u64;
+            }
+        }
+        let mut avg = [0u8; 4];
+        for c in 0..4 {
+            avg[c] = (sum[c] / total.max(1)) as u8;
+        }
+        avg
+    }
+
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let axis = self.longest_axis();
+        self.colors
+            .sort_by_key(|(color, _)| color[axis]);
+        let total: u64 = self.colors.iter().map(|(_, n)| *n as u64).sum();
+        let mut acc = 0u64;
+        let mut split_at = self.colors.len() / 2;
+        for (i, (_, n)) in self.colors.iter().enumerate() {
+            acc += *n as u64;
+            if acc * 2 >= total {
+                split_at = i + 1;
+                break;
+            }
+        }
+        let split_at = split_at.clamp(1, self.colors.len() - 1);
+        let right = self.colors.split_off(split_at);
+        (ColorBox { colors: self.colors }, ColorBox { colors: right })
+    }
+}
+
+/// Runs median-cut quantization over `image`'s RGBA pixels, returning the reduced (≤256 color)
+/// palette and the palette index chosen for every pixel (with Floyd–Steinberg error diffusion
+/// applied first when `dither` is set). Returns `None` when the image already has 256 colors or
+/// fewer, since there's nothing to gain from quantizing it further.
+pub fn median_cut(image: &IconImage, dither: bool) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let rgba = image.rgba_data();
+
+    let mut histogram: std::collections::HashMap<[u8; 4], u32> = std::collections::HashMap::new();
+    for pixel in rgba.chunks_exact(4) {
+        *histogram.entry([pixel[0], pixel[1], pixel[2], pixel[3]]).or_insert(0) += 1;
+    }
+
+    if histogram.len() <= MAX_COLORS {
+        return None;
+    }
+
+    let mut boxes = vec![ColorBox {
+        colors: histogram.into_iter().collect(),
+    }];
+    while boxes.len() < MAX_COLORS {
+        let splittable = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by(|(_, a), (_, b)| {
+                a.weighted_variance()
+                    .partial_cmp(&b.weighted_variance())
+                    .unwrap()
+            })
+            .map(|(i, _)| i);
+
+        let Some(index) = splittable else { break };
+        let (left, right) = boxes.remove(index).split();
+        boxes.push(left);
+        boxes.push(right);
+    }
+
+    let palette: Vec<[u8; 4]> = boxes.iter().map(ColorBox::average).collect();
+
+    let mut buffer: Vec<[i32; 4]> = rgba
+        .chunks_exact(4)
+        .map(|p| [p[0] as i32, p[1] as i32, p[2] as i32, p[3] as i32])
+        .collect();
+
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let pixel = buffer[i].map(|c| c.clamp(0, 255) as u8);
+            let index = nearest_palette_index(&palette, pixel);
+            indices.push(index as u8);
+
+            if dither {
+                let chosen = palette[index];
+                let error = [
+                    pixel[0] as i32 - chosen[0] as i32,
+                    pixel[1] as i32 - chosen[1] as i32,
+                    pixel[2] as i32 - chosen[2] as i32,
+                    pixel[3] as i32 - chosen[3] as i32,
+                ];
+                diffuse(&mut buffer, width, height, x, y, error);
+            }
+        }
+    }
+
+    Some((palette, indices))
+}
+
+fn diffuse(buffer: &mut [[i32; 4]], width: usize, height: usize, x: usize, y: usize, error: [i32; 4]) {
+    let mut spread = |dx: isize, dy: isize, weight: i32| {
+        let nx = x as isize + dx;
+        let ny = y as isize + dy;
+        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            return;
+        }
+        let n = ny as usize * width + nx as usize;
+        for c in 0..4 {
+            buffer[n][c] += error[c] * weight / 16;
+        }
+    };
+
+    spread(1, 0, 7);
+    spread(-1, 1, 3);
+    spread(0, 1, 5);
+    spread(1, 1, 1);
+}
+
+fn nearest_palette_index(palette: &[[u8; 4]], color: [u8; 4]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            (0..4)
+                .map(|c| (entry[c] as i32 - color[c] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// A rough 0-100 quality score derived from the mean squared error introduced by snapping every
+/// pixel to its nearest palette entry, used to decide whether `--quantize`'s minimum quality is
+/// met.
+pub fn quality_score(image: &IconImage, palette: &[[u8; 4]], indices: &[u8]) -> u8 {
+    let rgba = image.rgba_data();
+    let mut sum_squared_error = 0f64;
+    for (pixel, &index) in rgba.chunks_exact(4).zip(indices) {
+        let chosen = palette[index as usize];
+        for c in 0..4 {
+            let diff = pixel[c] as f64 - chosen[c] as f64;
+            sum_squared_error += diff * diff;
+        }
+    }
+    let pixel_count = indices.len().max(1) as f64;
+    let mse = sum_squared_error / (pixel_count * 4.0);
+    let normalized = (mse / (255.0 * 255.0)).min(1.0);
+    (100.0 * (1.0 - normalized)).round() as u8
+}
+
+/// Quantizes `image` if doing so can meet `range.min` quality, applying Floyd–Steinberg dithering
+/// unless the undithered result already reaches `range.max`, and encodes the result as a real
+/// 8-bit indexed PNG (see the module doc comment). Returns `None` if quantization was skipped,
+/// i.e. the caller should fall back to `IconDirEntry::encode_as_png` on the original image.
+pub fn quantize_image(image: &IconImage, range: QualityRange) -> Option<Result<Vec<u8>>> {
+    let (plain_palette, plain_indices) = median_cut(image, false)?;
+    let plain_quality = quality_score(image, &plain_palette, &plain_indices);
+
+    // Dithering is skipped once the undithered result already reaches `range.max`; otherwise the
+    // dithered result is used as long as it still meets `range.min`, falling back to the
+    // undithered one when it does not.
+    let (palette, indices, quality) = if plain_quality >= range.max {
+        (plain_palette, plain_indices, plain_quality)
+    } else {
+        let (dithered_palette, dithered_indices) = median_cut(image, true)?;
+        let dithered_quality = quality_score(image, &dithered_palette, &dithered_indices);
+        if dithered_quality >= range.min {
+            (dithered_palette, dithered_indices, dithered_quality)
+        } else {
+            (plain_palette, plain_indices, plain_quality)
+        }
+    };
+
+    if quality < range.min {
+        return None;
+    }
+
+    Some(encode_indexed_png(image.width(), image.height(), &palette, &indices))
+}
+
+/// Encodes a palette-mapped buffer as a real 8-bit indexed PNG: an 8-bit `PLTE` entry per palette
+/// color, a `tRNS` table carrying each entry's alpha (PNG has no per-pixel alpha in indexed mode),
+/// and the index buffer as the single `IDAT`. This is the literal "indexed PNG" this crate's own
+/// PNG encoder can't produce on its own.
+fn encode_indexed_png(width: u32, height: u32, palette: &[[u8; 4]], indices: &[u8]) -> Result<Vec<u8>> {
+    let mut rgb_palette = Vec::with_capacity(palette.len() * 3);
+    let mut alpha_palette = Vec::with_capacity(palette.len());
+    for color in palette {
+        rgb_palette.extend_from_slice(&color[..3]);
+        alpha_palette.push(color[3]);
+    }
+
+    let mut data = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut data, width, height);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(rgb_palette);
+        encoder.set_trns(alpha_palette);
+        let mut writer = encoder.write_header().context("cannot write indexed PNG header")?;
+        writer
+            .write_image_data(indices)
+            .context("cannot write indexed PNG image data")?;
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,80 @@
+//! Bilinear resampling used by `--scale` to fit a loaded cursor image to its config's declared
+//! nominal size (e.g. deriving a 48px cursor from a single 256px master PNG), scaling the hotspot
+//! along with the image so it keeps pointing at the same logical spot.
+
+use riff_ani::ico::IconImage;
+
+pub fn resize(image: &IconImage, target: u16) -> IconImage {
+    let src_width = image.width();
+    let src_height = image.height();
+    let dst_width = target as u32;
+    let dst_height = target as u32;
+
+    let src = image.rgba_data();
+    let mut dst = vec![0u8; dst_width as usize * dst_height as usize * 4];
+
+    for y in 0..dst_height {
+        for x in 0..dst_width {
+            let sx = (x as f32 + 0.5) * src_width as f32 / dst_width as f32 - 0.5;
+            let sy = (y as f32 + 0.5) * src_height as f32 / dst_height as f32 - 0.5;
+            let pixel = sample_bilinear(src, src_width, src_height, sx, sy);
+            let offset = (y * dst_width + x) as usize * 4;
+            dst[offset..offset + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    let mut resized = IconImage::from_rgba_data(dst_width, dst_height, dst);
+
+    if let Some((x_hot, y_hot)) = image.cursor_hotspot() {
+        let scaled_x = (x_hot as f32 * dst_width as f32 / src_width as f32).round() as u16;
+        let scaled_y = (y_hot as f32 * dst_height as f32 / src_height as f32).round() as u16;
+        resized.set_cursor_hotspot(Some((scaled_x, scaled_y)));
+    }
+
+    resized
+}
+
+/// Interpolates in premultiplied alpha space and unpremultiplies the result. Cursor PNGs are
+/// almost always mostly-transparent with arbitrary RGB under alpha=0, so blending straight
+/// (non-premultiplied) colors would mix a transparent texel's garbage RGB into its opaque
+/// neighbor and produce dark/black fringing along every edge.
+fn sample_bilinear(src: &[u8], width: u32, height: u32, x: f32, y: f32) -> [u8; 4] {
+    let x0 = x.floor().clamp(0.0, width as f32 - 1.0) as u32;
+    let y0 = y.floor().clamp(0.0, height as f32 - 1.0) as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+
+    let fx = (x - x0 as f32).clamp(0.0, 1.0);
+    let fy = (y - y0 as f32).clamp(0.0, 1.0);
+
+    let p00 = premultiplied_pixel_at(src, width, x0, y0);
+    let p10 = premultiplied_pixel_at(src, width, x1, y0);
+    let p01 = premultiplied_pixel_at(src, width, x0, y1);
+    let p11 = premultiplied_pixel_at(src, width, x1, y1);
+
+    let mut out = [0f32; 4];
+    for c in 0..4 {
+        let top = p00[c] * (1.0 - fx) + p10[c] * fx;
+        let bottom = p01[c] * (1.0 - fx) + p11[c] * fx;
+        out[c] = top * (1.0 - fy) + bottom * fy;
+    }
+
+    let alpha = out[3];
+    let mut pixel = [0u8; 4];
+    for c in 0..3 {
+        pixel[c] = if alpha > 0.0 { (out[c] / alpha).round().clamp(0.0, 255.0) as u8 } else { 0 };
+    }
+    pixel[3] = alpha.round().clamp(0.0, 255.0) as u8;
+    pixel
+}
+
+fn premultiplied_pixel_at(src: &[u8], width: u32, x: u32, y: u32) -> [f32; 4] {
+    let offset = (y * width + x) as usize * 4;
+    let alpha = src[offset + 3] as f32;
+    [
+        src[offset] as f32 * alpha / 255.0,
+        src[offset + 1] as f32 * alpha / 255.0,
+        src[offset + 2] as f32 * alpha / 255.0,
+        alpha,
+    ]
+}
@@ -0,0 +1,113 @@
+//! Parser for compiled X11 Xcursor binaries, so an existing Xcursor theme can be converted
+//! straight into the equivalent .cur/.ani instead of having to re-export every frame as PNG first.
+//!
+//! The file starts with magic `Xcur`, a u32 header size, a u32 version, and a u32 table-of-contents
+//! count. Each TOC entry is `(type, subtype, position)`; image chunks have `type == 0xfffd0002` and
+//! `subtype` equal to the nominal cursor size. At `position`, a 36-byte chunk header (size, type,
+//! subtype, version, width, height, xhot, yhot, delay_ms, all u32 little-endian) is followed by
+//! `width * height` little-endian, non-premultiplied ARGB32 pixels.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use riff_ani::ico::IconImage;
+
+use crate::{FrameConfig, FrameSource};
+
+pub const MAGIC: &[u8; 4] = b"Xcur";
+
+const CHUNK_TYPE_IMAGE: u32 = 0xfffd0002;
+const IMAGE_CHUNK_HEADER_SIZE: u32 = 36;
+const MAX_DIMENSION: u32 = 32767;
+
+pub fn parse(path: &Path) -> Result<HashMap<u16, Vec<FrameConfig>>> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("cannot read Xcursor file {}", path.to_string_lossy()))?;
+
+    if data.get(0..4) != Some(MAGIC.as_slice()) {
+        return Err(anyhow!("not an Xcursor file: bad magic"));
+    }
+
+    let header_size = read_u32(&data, 4)? as usize;
+    let _version = read_u32(&data, 8)?;
+    let toc_count = read_u32(&data, 12)? as usize;
+
+    let mut result: HashMap<u16, Vec<FrameConfig>> = HashMap::new();
+    for i in 0..toc_count {
+        let entry = header_size + i * 12;
+        let chunk_type = read_u32(&data, entry)?;
+        let subtype = read_u32(&data, entry + 4)?;
+        let position = read_u32(&data, entry + 8)? as usize;
+
+        if chunk_type != CHUNK_TYPE_IMAGE {
+            continue;
+        }
+
+        let size = subtype.min(u16::MAX as u32) as u16;
+        let frame = parse_image_chunk(&data, position, size)
+            .with_context(|| format!("invalid image chunk at offset {}", position))?;
+
+        result.entry(size).or_insert_with(Vec::new).push(frame);
+    }
+
+    Ok(result)
+}
+
+fn parse_image_chunk(data: &[u8], position: usize, size: u16) -> Result<FrameConfig> {
+    let chunk_size = read_u32(data, position)?;
+    let chunk_type = read_u32(data, position + 4)?;
+    let _subtype = read_u32(data, position + 8)?;
+    let _version = read_u32(data, position + 12)?;
+
+    if chunk_size != IMAGE_CHUNK_HEADER_SIZE || chunk_type != CHUNK_TYPE_IMAGE {
+        return Err(anyhow!("unexpected image chunk header"));
+    }
+
+    let width = read_u32(data, position + 16)?;
+    let height = read_u32(data, position + 20)?;
+    let xhot = read_u32(data, position + 24)?;
+    let yhot = read_u32(data, position + 28)?;
+    let delay_ms = read_u32(data, position + 32)?;
+
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(anyhow!(
+            "image {}x{} exceeds the Xcursor size limit of {}",
+            width,
+            height,
+            MAX_DIMENSION
+        ));
+    }
+
+    let pixels_offset = position + IMAGE_CHUNK_HEADER_SIZE as usize;
+    let pixels_len = width as usize * height as usize * 4;
+    let pixels = data
+        .get(pixels_offset..pixels_offset + pixels_len)
+        .ok_or_else(|| anyhow!("truncated pixel data"))?;
+
+    let mut rgba = Vec::with_capacity(pixels_len);
+    for argb in pixels.chunks_exact(4) {
+        let pixel = u32::from_le_bytes(argb.try_into().unwrap());
+        rgba.extend_from_slice(&[
+            (pixel >> 16) as u8,
+            (pixel >> 8) as u8,
+            pixel as u8,
+            (pixel >> 24) as u8,
+        ]);
+    }
+
+    Ok(FrameConfig {
+        size,
+        x_hot: xhot.min(u16::MAX as u32) as u16,
+        y_hot: yhot.min(u16::MAX as u32) as u16,
+        source: FrameSource::Inline(IconImage::from_rgba_data(width, height, rgba)),
+        ms_delay: delay_ms,
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("unexpected end of Xcursor file"))
+}